@@ -1,22 +1,29 @@
 use anyhow::Result;
 use axum::{
+    body::Bytes,
     extract::{ws::rejection::WebSocketUpgradeRejection, Query, State, WebSocketUpgrade},
     http::Method,
-    response::Json,
     routing::get,
     Router,
 };
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::{compression::CompressionLayer, trace::TraceLayer};
 
+use crate::auth::{self, AuthState};
+use crate::cache::Cache;
 use crate::db::ConnectionPool;
 use crate::interfaces::{AppError, AppState, QueryParams, QueryResponse};
+use crate::metrics;
 use crate::query;
 use crate::websocket;
 
+// Default cap on a request body, enforced before it's ever buffered.
+const DEFAULT_MAX_BODY_BYTES: usize = 5 * 1024 * 1024;
+
 #[axum::debug_handler]
 async fn handle_get(
     State(state): State<Arc<AppState>>,
@@ -25,40 +32,62 @@ async fn handle_get(
 ) -> Result<QueryResponse, AppError> {
     if let Ok(ws) = ws {
         // WebSocket upgrade
-        Ok(QueryResponse::Response(
+        Ok(QueryResponse::WebSocket(
             ws.on_upgrade(|socket| websocket::handle(socket, state)),
         ))
     } else {
         // HTTP request
-        query::handle(&state, params).await
+        params.validate()?;
+        let query_type = params.query_type.clone();
+        metrics::instrument_query(&query_type, query::handle(&state, params)).await
     }
 }
 
 #[axum::debug_handler]
 async fn handle_post(
     State(state): State<Arc<AppState>>,
-    Json(params): Json<QueryParams>,
+    body: Bytes,
 ) -> Result<QueryResponse, AppError> {
-    query::handle(&state, params).await
+    let params: QueryParams = serde_json::from_slice(&body)
+        .map_err(|error| AppError::BadRequest(format!("invalid JSON body: {error}")))?;
+    params.validate()?;
+    let query_type = params.query_type.clone();
+    metrics::instrument_query(&query_type, query::handle(&state, params)).await
 }
 
 pub fn app(
     dp_path: Option<&str>,
     connection_pool_size: Option<u32>,
     cache_size: Option<usize>,
+    cache_dir: Option<&Path>,
+    cache_disk_budget_bytes: Option<u64>,
+    max_request_bytes: Option<usize>,
+    auth_secret: Option<&str>,
 ) -> Result<Router> {
     // Database and state setup
     let db = ConnectionPool::new(
         dp_path.unwrap_or(":memory:"),
         connection_pool_size.unwrap_or(10),
     )?;
-    let cache = lru::LruCache::new(cache_size.unwrap_or(1000).try_into()?);
+    let cache = Cache::new(
+        cache_size.unwrap_or(1000),
+        cache_dir.map(Path::to_path_buf),
+        cache_disk_budget_bytes,
+    )?;
 
     let state = Arc::new(AppState {
         db: Box::new(db),
-        cache: Mutex::new(cache),
+        cache,
     });
 
+    // Metrics setup
+    let metrics_handle = metrics::install_recorder();
+
+    // Auth setup
+    let auth_state = AuthState {
+        secret: auth_secret.map(Into::into),
+    };
+
     // CORS setup
     let cors = CorsLayer::new()
         .allow_origin(Any)
@@ -69,7 +98,18 @@ pub fn app(
     // Router setup
     Ok(Router::new()
         .route("/", get(handle_get).post(handle_post))
+        .route_layer(axum::middleware::from_fn_with_state(
+            auth_state,
+            auth::middleware,
+        ))
         .with_state(state)
+        .merge(metrics::router(metrics_handle))
+        // The `Bytes` extractor otherwise applies axum's own 2 MB default,
+        // which would override a larger `max_request_bytes`.
+        .layer(axum::extract::DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(
+            max_request_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES),
+        ))
         .layer(cors)
         .layer(CompressionLayer::new())
         .layer(TraceLayer::new_for_http()))
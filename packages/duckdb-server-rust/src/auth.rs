@@ -0,0 +1,129 @@
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+
+// Separate middleware state from `AppState`; `secret: None` makes the layer
+// a no-op for deployments that don't configure one.
+#[derive(Clone)]
+pub struct AuthState {
+    pub secret: Option<Arc<str>>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Claims {
+    #[allow(dead_code)]
+    exp: usize,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+pub enum AuthError {
+    MissingToken,
+    InvalidToken,
+    Expired,
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let message = match self {
+            AuthError::MissingToken => "missing bearer token",
+            AuthError::InvalidToken => "invalid bearer token",
+            AuthError::Expired => "expired bearer token",
+        };
+        (StatusCode::UNAUTHORIZED, Json(json!({"error": message}))).into_response()
+    }
+}
+
+fn verify(secret: &str, token: &str) -> Result<(), AuthError> {
+    let key = DecodingKey::from_secret(secret.as_bytes());
+    decode::<Claims>(token, &key, &Validation::default())
+        .map(|_| ())
+        .map_err(|error| match error.kind() {
+            jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::Expired,
+            _ => AuthError::InvalidToken,
+        })
+}
+
+// Bearer token from the `Authorization` header, or from a `token`
+// query-string parameter for the WebSocket upgrade (browsers can't set
+// headers on `ws://`).
+pub async fn middleware(
+    State(auth): State<AuthState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AuthError> {
+    let Some(secret) = &auth.secret else {
+        return Ok(next.run(request).await);
+    };
+
+    let header_token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    let query_token = request
+        .uri()
+        .query()
+        .and_then(|query| serde_urlencoded::from_str::<TokenQuery>(query).ok())
+        .and_then(|parsed| parsed.token);
+
+    let token = header_token.or(query_token).ok_or(AuthError::MissingToken)?;
+    verify(secret, &token)?;
+
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    fn token(secret: &str, exp: usize) -> String {
+        encode(
+            &Header::default(),
+            &Claims { exp },
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn future_exp() -> usize {
+        (std::time::SystemTime::now() + std::time::Duration::from_secs(3600))
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as usize
+    }
+
+    #[test]
+    fn verify_accepts_a_valid_token() {
+        let token = token("secret", future_exp());
+        assert!(verify("secret", &token).is_ok());
+    }
+
+    #[test]
+    fn verify_rejects_an_expired_token() {
+        let token = token("secret", 0);
+        assert!(matches!(verify("secret", &token), Err(AuthError::Expired)));
+    }
+
+    #[test]
+    fn verify_rejects_a_token_signed_with_a_different_secret() {
+        let token = token("other-secret", future_exp());
+        assert!(matches!(
+            verify("secret", &token),
+            Err(AuthError::InvalidToken)
+        ));
+    }
+}
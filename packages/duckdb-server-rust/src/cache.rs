@@ -0,0 +1,162 @@
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+use crate::metrics;
+
+// Two-tier result cache: the in-memory LRU is the hot tier, backed by a
+// content-addressed directory on disk as the persistent tier.
+pub struct Cache {
+    hot: Mutex<lru::LruCache<String, Vec<u8>>>,
+    dir: Option<PathBuf>,
+    budget_bytes: Option<u64>,
+}
+
+impl Cache {
+    pub fn new(
+        capacity: usize,
+        dir: Option<PathBuf>,
+        budget_bytes: Option<u64>,
+    ) -> anyhow::Result<Self> {
+        if let Some(dir) = &dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            hot: Mutex::new(lru::LruCache::new(NonZeroUsize::try_from(capacity)?)),
+            dir,
+            budget_bytes,
+        })
+    }
+
+    pub fn key(sql: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(sql.trim());
+        format!("{:x}", hasher.finalize())
+    }
+
+    // Consult the hot tier, then the disk tier, reloading a disk hit back
+    // into the LRU.
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        if let Some(bytes) = self.hot.lock().await.get(key) {
+            metrics::record_cache_lookup(true);
+            return Some(bytes.clone());
+        }
+        match self.read_disk(key).await {
+            Some(bytes) => {
+                metrics::record_cache_lookup(true);
+                self.hot.lock().await.put(key.to_string(), bytes.clone());
+                Some(bytes)
+            }
+            None => {
+                metrics::record_cache_lookup(false);
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, key: &str, bytes: Vec<u8>, persist: bool) {
+        if persist {
+            self.write_disk(key, &bytes).await;
+        }
+        self.hot.lock().await.put(key.to_string(), bytes);
+    }
+
+    fn path_for(&self, key: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{key}.arrow")))
+    }
+
+    async fn read_disk(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.path_for(key)?;
+        tokio::fs::read(path).await.ok()
+    }
+
+    async fn write_disk(&self, key: &str, bytes: &[u8]) {
+        let Some(path) = self.path_for(key) else {
+            return;
+        };
+        if let Err(error) = tokio::fs::write(&path, bytes).await {
+            tracing::warn!("failed to persist cache entry {:?}: {}", path, error);
+            return;
+        }
+        self.enforce_budget().await;
+    }
+
+    // Evicts the least-recently-modified files once the disk tier exceeds
+    // budget_bytes.
+    async fn enforce_budget(&self) {
+        let (Some(dir), Some(budget)) = (&self.dir, self.budget_bytes) else {
+            return;
+        };
+        let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+            return;
+        };
+        let mut files = Vec::new();
+        let mut total = 0u64;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if let Ok(metadata) = entry.metadata().await {
+                total += metadata.len();
+                let modified = metadata.modified().unwrap_or(std::time::SystemTime::UNIX_EPOCH);
+                files.push((entry.path(), metadata.len(), modified));
+            }
+        }
+        if total <= budget {
+            return;
+        }
+        files.sort_by_key(|(_, _, modified)| *modified);
+        for (path, size, _) in files {
+            if total <= budget {
+                break;
+            }
+            if tokio::fs::remove_file(&path).await.is_ok() {
+                total = total.saturating_sub(size);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mosaic-cache-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn key_is_deterministic_and_trims_whitespace() {
+        assert_eq!(Cache::key("select 1"), Cache::key("  select 1  "));
+        assert_ne!(Cache::key("select 1"), Cache::key("select 2"));
+    }
+
+    #[tokio::test]
+    async fn get_reloads_a_disk_hit_into_the_hot_tier() {
+        let dir = temp_dir("reload");
+        let cache = Cache::new(10, Some(dir.clone()), None).unwrap();
+        let key = Cache::key("select 1");
+        cache.put(&key, b"hello".to_vec(), true).await;
+
+        let other = Cache::new(10, Some(dir.clone()), None).unwrap();
+        assert_eq!(other.get(&key).await, Some(b"hello".to_vec()));
+        assert_eq!(other.hot.lock().await.get(&key), Some(&b"hello".to_vec()));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn enforce_budget_evicts_oldest_files_until_under_budget() {
+        let dir = temp_dir("evict");
+        let cache = Cache::new(10, Some(dir.clone()), Some(10)).unwrap();
+
+        cache.put("a", vec![0u8; 8], true).await;
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        cache.put("b", vec![0u8; 8], true).await;
+
+        assert!(cache.read_disk("b").await.is_some());
+        assert!(cache.read_disk("a").await.is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,18 +1,26 @@
 use axum::{
-    body::Bytes,
+    body::{Body, Bytes},
     http::StatusCode,
     response::{IntoResponse, Response},
+    Json,
 };
+use futures_util::Stream;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
 use crate::bundle::Query as BundleQuery;
+use crate::cache::Cache;
 use crate::db::Database;
 
+// A live sequence of Arrow IPC stream frames, forwarded to the client as
+// they're produced instead of being buffered into a single `Arrow` payload.
+pub type ArrowBatchStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>>;
+
 pub struct AppState {
     pub db: Arc<dyn Database>,
-    pub cache: Mutex<lru::LruCache<String, Vec<u8>>>,
+    pub cache: Cache,
 }
 
 #[derive(Deserialize, Serialize, Debug, Default)]
@@ -25,9 +33,31 @@ pub struct QueryParams {
     pub queries: Option<Vec<BundleQuery>>,
 }
 
+impl QueryParams {
+    // Checks the fields required by `query_type`, turning a missing field
+    // into a 400 instead of a panic or a generic 500 inside `query::handle`.
+    pub fn validate(&self) -> Result<(), AppError> {
+        match self.query_type.as_str() {
+            "exec" | "arrow" | "arrow-stream" if self.sql.is_none() => {
+                Err(AppError::BadRequest(format!(
+                    "missing required field `sql` for query type `{}`",
+                    self.query_type
+                )))
+            }
+            "bundle" if self.queries.is_none() => Err(AppError::BadRequest(
+                "missing required field `queries` for query type `bundle`".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
 pub enum QueryResponse {
     Json(String),
     Arrow(Vec<u8>),
+    // Response to a `"type": "arrow-stream"` query: keeps peak memory bounded
+    // by one record batch rather than the full result table.
+    ArrowStream(ArrowBatchStream),
     WebSocket(Response),
     BadRequest,
     Empty,
@@ -48,23 +78,56 @@ impl IntoResponse for QueryResponse {
                 Bytes::from(bytes),
             )
                 .into_response(),
+            QueryResponse::ArrowStream(stream) => (
+                StatusCode::OK,
+                [(
+                    "Content-Type",
+                    "application/vnd.apache.arrow.stream",
+                )],
+                Body::from_stream(stream),
+            )
+                .into_response(),
             QueryResponse::WebSocket(response) => response,
-            QueryResponse::BadRequest => StatusCode::BAD_REQUEST.into_response(),
+            QueryResponse::BadRequest => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Bad request"})),
+            )
+                .into_response(),
             QueryResponse::Empty => StatusCode::OK.into_response(),
         }
     }
 }
 
-pub struct AppError(anyhow::Error);
+// A client mistake (bad input) vs. a server fault.
+pub enum AppError {
+    BadRequest(String),
+    Error(anyhow::Error),
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::BadRequest(message) => write!(f, "{message}"),
+            AppError::Error(error) => write!(f, "{error}"),
+        }
+    }
+}
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        tracing::error!("Error: {:?}", self.0);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        match self {
+            AppError::BadRequest(message) => {
+                (StatusCode::BAD_REQUEST, Json(json!({"error": message}))).into_response()
+            }
+            AppError::Error(error) => {
+                tracing::error!("Error: {:?}", error);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({"error": format!("Something went wrong: {}", error)})),
+                )
+                    .into_response()
+            }
+        }
     }
 }
 
@@ -73,6 +136,54 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        Self::Error(err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(query_type: &str) -> QueryParams {
+        QueryParams {
+            query_type: query_type.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_rejects_exec_missing_sql() {
+        assert!(matches!(
+            params("exec").validate(),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_arrow_stream_missing_sql() {
+        assert!(matches!(
+            params("arrow-stream").validate(),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_bundle_missing_queries() {
+        assert!(matches!(
+            params("bundle").validate(),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn validate_accepts_exec_with_sql() {
+        let mut query = params("exec");
+        query.sql = Some("select 1".to_string());
+        assert!(query.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_accepts_unrecognized_query_types() {
+        assert!(params("status").validate().is_ok());
     }
 }
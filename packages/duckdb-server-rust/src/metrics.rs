@@ -0,0 +1,99 @@
+use crate::interfaces::{AppError, QueryResponse};
+use axum::{routing::get, Router};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+static RECORDER: OnceLock<PrometheusHandle> = OnceLock::new();
+
+// Installs the process-wide Prometheus recorder on first call; later calls
+// (e.g. `app()` building more than one `Router`) reuse the same handle
+// instead of panicking on the already-installed global recorder.
+pub fn install_recorder() -> PrometheusHandle {
+    RECORDER
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .install_recorder()
+                .expect("failed to install Prometheus recorder")
+        })
+        .clone()
+}
+
+// GET /metrics in Prometheus text exposition format.
+pub fn router(handle: PrometheusHandle) -> Router {
+    Router::new().route("/metrics", get(move || async move { handle.render() }))
+}
+
+fn classify_outcome(result: &Result<QueryResponse, AppError>) -> &'static str {
+    match result {
+        Ok(QueryResponse::BadRequest) => "bad-request",
+        Ok(_) => "ok",
+        Err(AppError::BadRequest(_)) => "bad-request",
+        Err(AppError::Error(_)) => "error",
+    }
+}
+
+// Runs a query::handle call, recording a query_type/outcome counter and a
+// latency histogram around it.
+pub async fn instrument_query(
+    query_type: &str,
+    fut: impl Future<Output = Result<QueryResponse, AppError>>,
+) -> Result<QueryResponse, AppError> {
+    let start = Instant::now();
+    let result = fut.await;
+    let outcome = classify_outcome(&result);
+    metrics::counter!(
+        "mosaic_queries_total",
+        "query_type" => query_type.to_string(),
+        "outcome" => outcome,
+    )
+    .increment(1);
+    metrics::histogram!(
+        "mosaic_query_duration_seconds",
+        "query_type" => query_type.to_string(),
+    )
+    .record(start.elapsed().as_secs_f64());
+    result
+}
+
+// Recorded by the cache lookup in query::handle.
+pub fn record_cache_lookup(hit: bool) {
+    metrics::counter!("mosaic_cache_total", "outcome" => if hit { "hit" } else { "miss" })
+        .increment(1);
+}
+
+pub fn websocket_opened() {
+    metrics::gauge!("mosaic_websocket_connections").increment(1.0);
+}
+
+pub fn websocket_closed() {
+    metrics::gauge!("mosaic_websocket_connections").decrement(1.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_ok_as_ok() {
+        assert_eq!(classify_outcome(&Ok(QueryResponse::Empty)), "ok");
+    }
+
+    #[test]
+    fn classifies_query_response_bad_request_as_bad_request() {
+        assert_eq!(classify_outcome(&Ok(QueryResponse::BadRequest)), "bad-request");
+    }
+
+    #[test]
+    fn classifies_app_error_bad_request_as_bad_request() {
+        let result = Err(AppError::BadRequest("missing sql".to_string()));
+        assert_eq!(classify_outcome(&result), "bad-request");
+    }
+
+    #[test]
+    fn classifies_other_errors_as_error() {
+        let result = Err(AppError::Error(anyhow::anyhow!("boom")));
+        assert_eq!(classify_outcome(&result), "error");
+    }
+}
@@ -3,64 +3,274 @@ use crate::{
     AppState,
 };
 use axum::extract::ws::{Message, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
 use serde_json::json;
+use std::collections::HashMap;
 use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 
-pub async fn handle(mut socket: WebSocket, state: Arc<AppState>) {
-    while let Some(msg) = socket.recv().await {
-        if let Ok(msg) = msg {
-            match msg {
-                Message::Text(text) => {
-                    let response = handle_message(text, state.clone()).await;
-                    if match response {
-                        Err(error) => match error {
-                            AppError::BadRequest => {
-                                socket
-                                    .send(Message::Text(
-                                        json!({"error": "Bad request"}).to_string(),
-                                    ))
-                                    .await
-                            }
-                            AppError::Error(error) => {
-                                socket
-                                    .send(Message::Text(
-                                        json!({"error": format!("{}", error)}).to_string(),
-                                    ))
-                                    .await
-                            }
-                        },
-                        Ok(result) => match result {
-                            QueryResponse::Arrow(arrow) => {
-                                socket.send(Message::Binary(arrow)).await
-                            }
-                            QueryResponse::Json(json) => socket.send(Message::Text(json)).await,
-                            QueryResponse::Empty => {
-                                socket.send(Message::Text("{}".to_string())).await
-                            }
-                            QueryResponse::Response(_) => {
-                                socket
-                                    .send(Message::Text(
-                                        json!({"error": "Unknown response Type"}).to_string(),
-                                    ))
-                                    .await
-                            }
-                        },
+// Client-generated id, echoed back on every reply to a request; the error
+// reply to a frame that fails to parse carries no id.
+type RequestId = u32;
+
+// Bounds the reply channel so a fast producer can't OOM the connection.
+const REPLY_CHANNEL_CAPACITY: usize = 64;
+
+// GC threshold for the in-flight map.
+const INFLIGHT_GC_THRESHOLD: usize = 256;
+
+const FRAME_KIND_ARROW: u8 = 0;
+const FRAME_KIND_ARROW_STREAM_BATCH: u8 = 1;
+
+// Prefixes the payload with the request id and frame kind, since binary
+// frames from concurrent queries share one socket.
+fn binary_frame(id: RequestId, kind: u8, payload: Vec<u8>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.extend_from_slice(&id.to_be_bytes());
+    frame.push(kind);
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+// Removes `id` from `inflight` and aborts its task, unless the task already
+// ran to completion (the real reply was already sent, so a "cancelled" reply
+// would be spurious); the entry is removed either way. Returns whether a
+// genuine cancellation happened.
+fn abort_inflight(inflight: &mut HashMap<RequestId, JoinHandle<()>>, id: RequestId) -> bool {
+    match inflight.remove(&id) {
+        Some(task) if !task.is_finished() => {
+            task.abort();
+            true
+        }
+        _ => false,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum IncomingMessage {
+    Cancel(CancelRequest),
+    Query(RpcRequest),
+}
+
+#[derive(Deserialize, Debug)]
+struct CancelRequest {
+    #[serde(rename = "type")]
+    kind: CancelKind,
+    id: RequestId,
+}
+
+#[derive(Deserialize, Debug)]
+enum CancelKind {
+    #[serde(rename = "cancel")]
+    Cancel,
+}
+
+#[derive(Deserialize, Debug)]
+struct RpcRequest {
+    id: RequestId,
+    #[serde(flatten)]
+    params: crate::interfaces::QueryParams,
+}
+
+pub async fn handle(socket: WebSocket, state: Arc<AppState>) {
+    crate::metrics::websocket_opened();
+
+    let (mut sink, mut stream) = socket.split();
+    let (reply_tx, mut reply_rx) = mpsc::channel::<Message>(REPLY_CHANNEL_CAPACITY);
+
+    // A single task owns the sink, so replies from many concurrently running
+    // queries are serialized onto the socket instead of racing each other.
+    let sink_task = tokio::spawn(async move {
+        while let Some(message) = reply_rx.recv().await {
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut inflight: HashMap<RequestId, JoinHandle<()>> = HashMap::new();
+
+    while let Some(Ok(msg)) = stream.next().await {
+        match msg {
+            Message::Text(text) => match serde_json::from_str::<IncomingMessage>(&text) {
+                Ok(IncomingMessage::Cancel(cancel)) => {
+                    if abort_inflight(&mut inflight, cancel.id) {
+                        let _ = reply_tx
+                            .send(Message::Text(
+                                json!({"id": cancel.id, "type": "cancelled"}).to_string(),
+                            ))
+                            .await;
+                    }
+                }
+                Ok(IncomingMessage::Query(request)) => {
+                    let id = request.id;
+                    if let Err(error) = request.params.validate() {
+                        send_reply(&reply_tx, id, Err(error)).await;
+                        continue;
+                    }
+                    let query_type = request.params.query_type.clone();
+                    let task_state = state.clone();
+                    let task_reply_tx = reply_tx.clone();
+                    let task = tokio::spawn(async move {
+                        let response = crate::metrics::instrument_query(
+                            &query_type,
+                            crate::query::handle(&task_state, request.params),
+                        )
+                        .await;
+                        send_reply(&task_reply_tx, id, response).await;
+                    });
+                    inflight.insert(id, task);
+                }
+                Err(error) => {
+                    let _ = reply_tx
+                        .send(Message::Text(
+                            json!({"error": format!("{}", error)}).to_string(),
+                        ))
+                        .await;
+                }
+            },
+            Message::Close(_) => break,
+            _ => {}
+        }
+
+        if inflight.len() > INFLIGHT_GC_THRESHOLD {
+            inflight.retain(|_, task| !task.is_finished());
+        }
+    }
+
+    for (_, task) in inflight.drain() {
+        task.abort();
+    }
+    sink_task.abort();
+    crate::metrics::websocket_closed();
+}
+
+// Encodes a `query::handle` result into one or more reply frames tagged with
+// `id`, and pushes them onto the shared reply channel in order.
+async fn send_reply(
+    reply_tx: &mpsc::Sender<Message>,
+    id: RequestId,
+    response: Result<QueryResponse, AppError>,
+) {
+    match response {
+        Ok(QueryResponse::Arrow(bytes)) => {
+            let _ = reply_tx
+                .send(Message::Binary(binary_frame(id, FRAME_KIND_ARROW, bytes)))
+                .await;
+        }
+        Ok(QueryResponse::ArrowStream(mut stream)) => {
+            while let Some(batch) = stream.next().await {
+                match batch {
+                    Ok(bytes) => {
+                        let _ = reply_tx
+                            .send(Message::Binary(binary_frame(
+                                id,
+                                FRAME_KIND_ARROW_STREAM_BATCH,
+                                bytes.to_vec(),
+                            )))
+                            .await;
                     }
-                    .is_err()
-                    {
-                        break;
+                    Err(error) => {
+                        let _ = reply_tx
+                            .send(Message::Text(
+                                json!({"id": id, "error": error.to_string()}).to_string(),
+                            ))
+                            .await;
+                        return;
                     }
                 }
-                Message::Close(_) => break,
-                _ => {}
             }
-        } else {
-            break;
+            let _ = reply_tx
+                .send(Message::Text(
+                    json!({"id": id, "type": "arrow-stream-end"}).to_string(),
+                ))
+                .await;
+        }
+        Ok(QueryResponse::Json(value)) => {
+            let _ = reply_tx
+                .send(Message::Text(format!(
+                    "{{\"id\":{id},\"type\":\"json\",\"data\":{value}}}"
+                )))
+                .await;
+        }
+        Ok(QueryResponse::Empty) => {
+            let _ = reply_tx
+                .send(Message::Text(
+                    json!({"id": id, "type": "empty"}).to_string(),
+                ))
+                .await;
+        }
+        Ok(QueryResponse::BadRequest) => {
+            let _ = reply_tx
+                .send(Message::Text(
+                    json!({"id": id, "error": "Bad request"}).to_string(),
+                ))
+                .await;
+        }
+        Ok(QueryResponse::WebSocket(_)) => {
+            let _ = reply_tx
+                .send(Message::Text(
+                    json!({"id": id, "error": "Unexpected response type"}).to_string(),
+                ))
+                .await;
+        }
+        Err(error) => {
+            let _ = reply_tx
+                .send(Message::Text(
+                    json!({"id": id, "error": format!("{}", error)}).to_string(),
+                ))
+                .await;
         }
     }
 }
 
-async fn handle_message(message: String, state: Arc<AppState>) -> Result<QueryResponse, AppError> {
-    let params = serde_json::from_str(&message)?;
-    crate::query::handle(state, params).await
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_frame_prefixes_id_and_kind() {
+        let frame = binary_frame(0x01020304, FRAME_KIND_ARROW, vec![9, 9]);
+        assert_eq!(frame, vec![0x01, 0x02, 0x03, 0x04, FRAME_KIND_ARROW, 9, 9]);
+    }
+
+    #[test]
+    fn binary_frame_distinguishes_stream_batches_from_full_arrow_replies() {
+        let frame = binary_frame(1, FRAME_KIND_ARROW_STREAM_BATCH, vec![]);
+        assert_eq!(frame[4], FRAME_KIND_ARROW_STREAM_BATCH);
+    }
+
+    #[tokio::test]
+    async fn abort_inflight_cancels_a_still_running_task() {
+        let mut inflight = HashMap::new();
+        let task = tokio::spawn(async {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+        });
+        inflight.insert(1, task);
+
+        assert!(abort_inflight(&mut inflight, 1));
+        assert!(inflight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn abort_inflight_drops_an_already_finished_task_without_cancelling() {
+        let mut inflight = HashMap::new();
+        let task = tokio::spawn(async {});
+        while !task.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        inflight.insert(1, task);
+
+        assert!(!abort_inflight(&mut inflight, 1));
+        assert!(inflight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn abort_inflight_is_a_no_op_for_an_unknown_id() {
+        let mut inflight: HashMap<RequestId, JoinHandle<()>> = HashMap::new();
+        assert!(!abort_inflight(&mut inflight, 42));
+    }
 }